@@ -0,0 +1,3 @@
+pub use self::source::GitReference;
+
+pub mod source;
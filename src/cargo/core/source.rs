@@ -0,0 +1,202 @@
+use std::fmt;
+use std::path::{Component, Path};
+
+use url::Url;
+
+use core::{Package, PackageId, Registry};
+use util::{human, CargoResult, ToUrl};
+
+/// A source of packages: a registry, a local path, or a git repository.
+pub trait Source: Registry {
+    /// Fetch/refresh this source so that `query` and `download` can be
+    /// answered without touching the network again.
+    fn update(&mut self) -> CargoResult<()>;
+
+    /// Fetch the full `Package` for `id`, which must have come from this
+    /// source's own `query`.
+    fn download(&mut self, id: &PackageId) -> CargoResult<Package>;
+
+    /// An opaque string that changes whenever `pkg`'s contents do, used to
+    /// detect whether a cached checkout is still up to date.
+    fn fingerprint(&self, pkg: &Package) -> CargoResult<String>;
+}
+
+/// How a git dependency is pinned: to the tip of a branch, to a tag, or to
+/// an exact revision.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum GitReference {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl GitReference {
+    pub fn to_ref_string(&self) -> Option<String> {
+        match *self {
+            GitReference::Branch(ref s) => Some(format!("branch={}", s)),
+            GitReference::Tag(ref s) => Some(format!("tag={}", s)),
+            GitReference::Rev(ref s) => Some(format!("rev={}", s)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Kind {
+    Git(Url, GitReference, Option<String>),
+    Other,
+}
+
+/// Where a package comes from: a registry, a local path, or a git repository.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SourceId {
+    kind: Kind,
+    precise: Option<String>,
+}
+
+impl SourceId {
+    /// Builds a `SourceId` for a git dependency. `subdir` is the relative
+    /// path, within the repository, that the crate actually lives at —
+    /// `None` means the crate sits at the repo root. This is populated from
+    /// the dependency's `subdir = "..."` manifest key (see
+    /// `GitDependencyDetail::to_source_id` below).
+    pub fn for_git(url: &Url, reference: GitReference, subdir: Option<String>) -> SourceId {
+        SourceId {
+            kind: Kind::Git(url.clone(), reference, subdir),
+            precise: None,
+        }
+    }
+
+    pub fn is_git(&self) -> bool {
+        match self.kind {
+            Kind::Git(..) => true,
+            Kind::Other => false,
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        match self.kind {
+            Kind::Git(ref url, _, _) => url,
+            Kind::Other => panic!("not a git source"),
+        }
+    }
+
+    pub fn git_reference(&self) -> Option<&GitReference> {
+        match self.kind {
+            Kind::Git(_, ref reference, _) => Some(reference),
+            Kind::Other => None,
+        }
+    }
+
+    pub fn git_subdir(&self) -> Option<&str> {
+        match self.kind {
+            Kind::Git(_, _, ref subdir) => subdir.as_ref().map(|s| s.as_str()),
+            Kind::Other => None,
+        }
+    }
+
+    pub fn precise(&self) -> Option<&str> {
+        self.precise.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn with_precise(&self, precise: Option<String>) -> SourceId {
+        SourceId { kind: self.kind.clone(), precise: precise }
+    }
+}
+
+impl fmt::Display for SourceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Git(ref url, ref reference, ref subdir) => {
+                try!(write!(f, "{}", url));
+                if let Some(s) = reference.to_ref_string() {
+                    try!(write!(f, "?{}", s));
+                }
+                if let Some(ref subdir) = *subdir {
+                    try!(write!(f, "#{}", subdir));
+                }
+                Ok(())
+            }
+            Kind::Other => write!(f, "<source>"),
+        }
+    }
+}
+
+/// The `git = "..."` (or `[dependencies.foo]` table) shape of a git
+/// dependency as it comes off the TOML manifest. The rest of manifest
+/// parsing lives in `util::toml`; this is just the sliver that feeds
+/// `SourceId::for_git`.
+#[derive(Clone, Debug)]
+pub struct GitDependencyDetail {
+    pub git: String,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    /// `subdir = "..."`: the crate lives in a subdirectory of the repo
+    /// instead of at its root (monorepo-style git dependencies).
+    pub subdir: Option<String>,
+}
+
+impl GitDependencyDetail {
+    pub fn to_source_id(&self) -> CargoResult<SourceId> {
+        let url = try!(self.git.to_url());
+        let reference = if let Some(ref branch) = self.branch {
+            GitReference::Branch(branch.clone())
+        } else if let Some(ref tag) = self.tag {
+            GitReference::Tag(tag.clone())
+        } else if let Some(ref rev) = self.rev {
+            GitReference::Rev(rev.clone())
+        } else {
+            GitReference::Branch("master".to_string())
+        };
+        let subdir = match self.subdir {
+            Some(ref s) => Some(try!(validate_subdir(s))),
+            None => None,
+        };
+        Ok(SourceId::for_git(&url, reference, subdir))
+    }
+}
+
+/// Rejects a manifest's `subdir = "..."` unless it's a plain relative path
+/// that stays inside the checkout: no absolute paths (which would make
+/// `Path::join` discard the checkout root entirely) and no `..` components
+/// (which would walk back out of it).
+fn validate_subdir(subdir: &str) -> CargoResult<String> {
+    let path = Path::new(subdir);
+    if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return Err(human(format!("invalid `subdir = \"{}\"`; it must be a \
+                                   relative path inside the repository",
+                                  subdir)));
+    }
+    Ok(subdir.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::GitDependencyDetail;
+
+    fn detail(subdir: Option<&str>) -> GitDependencyDetail {
+        GitDependencyDetail {
+            git: "https://github.com/rust-lang/cargo".to_string(),
+            branch: None,
+            tag: None,
+            rev: None,
+            subdir: subdir.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn to_source_id_accepts_a_relative_subdir() {
+        let id = detail(Some("crates/foo")).to_source_id().unwrap();
+        assert_eq!(id.git_subdir(), Some("crates/foo"));
+    }
+
+    #[test]
+    fn to_source_id_rejects_an_absolute_subdir() {
+        assert!(detail(Some("/etc")).to_source_id().is_err());
+    }
+
+    #[test]
+    fn to_source_id_rejects_a_subdir_that_escapes_the_checkout() {
+        assert!(detail(Some("../../../etc")).to_source_id().is_err());
+    }
+}
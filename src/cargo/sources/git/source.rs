@@ -1,4 +1,5 @@
 use std::fmt::{self, Debug, Formatter};
+use std::path::{Path, PathBuf};
 
 use url::Url;
 
@@ -28,7 +29,7 @@ impl<'cfg> GitSource<'cfg> {
         assert!(source_id.is_git(), "id is not git, id={}", source_id);
 
         let remote = GitRemote::new(source_id.url());
-        let ident = ident(source_id.url());
+        let ident = ident(source_id.url(), config);
 
         let reference = match source_id.precise() {
             Some(s) => GitReference::Rev(s.to_string()),
@@ -56,8 +57,8 @@ impl<'cfg> GitSource<'cfg> {
     }
 }
 
-fn ident(url: &Url) -> String {
-    let url = canonicalize_url(url);
+fn ident(url: &Url, config: &Config) -> String {
+    let url = canonicalize_url(url, config);
     let ident = url.path_segments().and_then(|mut s| s.next_back()).unwrap_or("");
 
     let ident = if ident == "" {
@@ -69,8 +70,35 @@ fn ident(url: &Url) -> String {
     format!("{}-{}", ident, short_hash(&url))
 }
 
+// Hosts that are known (or configured) to treat repository paths
+// case-insensitively, so that e.g. `.../Foo/bar` and `.../foo/Bar` hash to
+// the same `ident` and share one checkout. The built-in defaults can be
+// extended via the `net.git-case-insensitive-hosts` config list; hosts not
+// in this table are left untouched so we don't collapse distinct repos on
+// hosts that actually do distinguish case.
+fn case_insensitive_hosts(config: &Config) -> Vec<String> {
+    let mut hosts = vec!["github.com".to_string(),
+                         "gitlab.com".to_string(),
+                         "bitbucket.org".to_string()];
+    match config.get_list("net.git-case-insensitive-hosts") {
+        Ok(Some(extra)) => {
+            hosts.extend(extra.val.into_iter().map(|(s, _)| s.to_lowercase()));
+        }
+        Ok(None) => {}
+        // A malformed config value shouldn't take the whole operation down,
+        // but silently falling back to the built-in hosts would leave the
+        // user's config quietly ignored, so let them know.
+        Err(e) => {
+            let _ = config.shell().warn(format!(
+                "failed to parse `net.git-case-insensitive-hosts`, \
+                 ignoring it: {}", e));
+        }
+    }
+    hosts
+}
+
 // Some hacks and heuristics for making equivalent URLs hash the same
-pub fn canonicalize_url(url: &Url) -> Url {
+pub fn canonicalize_url(url: &Url, config: &Config) -> Url {
     let mut url = url.clone();
 
     // Strip a trailing slash
@@ -78,15 +106,16 @@ pub fn canonicalize_url(url: &Url) -> Url {
         url.path_segments_mut().unwrap().pop_if_empty();
     }
 
-    // HACKHACK: For github URL's specifically just lowercase
-    // everything.  GitHub treats both the same, but they hash
-    // differently, and we're gonna be hashing them. This wants a more
-    // general solution, and also we're almost certainly not using the
-    // same case conversion rules that GitHub does. (#84)
-    if url.host_str() == Some("github.com") {
-        url.set_scheme("https").unwrap();
-        let path = url.path().to_lowercase();
-        url.set_path(&path);
+    // For hosts known to treat paths case-insensitively, lowercase the path
+    // (and force https) so that differently-cased URLs to the same repo
+    // hash the same. See `case_insensitive_hosts` above for which hosts count.
+    if let Some(host) = url.host_str().map(|h| h.to_lowercase()) {
+        if case_insensitive_hosts(config).iter().any(|h| *h == host) {
+            url.set_scheme("https").unwrap();
+            url.set_host(Some(&host)).unwrap();
+            let path = url.path().to_lowercase();
+            url.set_path(&path);
+        }
     }
 
     // Repos generally can be accessed with or w/o '.git'
@@ -102,6 +131,15 @@ pub fn canonicalize_url(url: &Url) -> Url {
     url
 }
 
+/// The directory a dependency's `PathSource` should actually walk: the repo
+/// checkout itself, or a subdirectory of it when the `SourceId` names one.
+fn pkg_root(checkout_path: &Path, subdir: Option<&str>) -> PathBuf {
+    match subdir {
+        Some(subdir) => checkout_path.join(subdir),
+        None => checkout_path.to_path_buf(),
+    }
+}
+
 impl<'cfg> Debug for GitSource<'cfg> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         try!(write!(f, "git repo at {}", self.remote.url()));
@@ -144,14 +182,34 @@ impl<'cfg> Source for GitSource<'cfg> {
         let should_update = actual_rev.is_err() ||
                             self.source_id.precise().is_none();
 
+        // A concrete branch/tag/rev doesn't need the full history, so try
+        // fetching just the tip first. `Branch`/`Tag` resolve by a direct
+        // ref lookup that doesn't care about history depth, so they always
+        // succeed here; a pinned `Rev` only succeeds if it happens to be
+        // reachable within the shallow fetch, and the fallback below
+        // deepens and retries when it isn't.
+        let depth = Some(1);
+
         let (repo, actual_rev) = if should_update {
             try!(self.config.shell().status("Updating",
                 format!("git repository `{}`", self.remote.url())));
 
             trace!("updating git source `{:?}`", self.remote);
 
-            let repo = try!(self.remote.checkout(&db_path, &self.config));
-            let rev = try!(repo.rev_for(&self.reference));
+            let repo = try!(self.remote.checkout(&db_path, &self.config, depth, &self.reference));
+            let resolved = repo.rev_for(&self.reference);
+            let (repo, rev) = match resolved {
+                Ok(rev) => (repo, rev),
+                // The pinned rev wasn't reachable within our shallow depth;
+                // deepen the db with a full fetch and try again.
+                Err(..) if repo.shallow() => {
+                    let repo = try!(self.remote.checkout(&db_path, &self.config, None,
+                                                          &self.reference));
+                    let rev = try!(repo.rev_for(&self.reference));
+                    (repo, rev)
+                }
+                Err(e) => return Err(e),
+            };
             (repo, rev)
         } else {
             (try!(self.remote.db_at(&db_path)), actual_rev.unwrap())
@@ -163,7 +221,14 @@ impl<'cfg> Source for GitSource<'cfg> {
         try!(repo.copy_to(actual_rev.clone(), &checkout_path, &self.config));
 
         let source_id = self.source_id.with_precise(Some(actual_rev.to_string()));
-        let path_source = PathSource::new_recursive(&checkout_path,
+
+        // The crate we're after might not live at the root of the repo, e.g.
+        // when pulling one package out of a larger monorepo-style workspace.
+        // We still check out (and keep) the whole repo so that path
+        // dependencies relative to the crate's own `Cargo.toml` resolve, but
+        // the `PathSource` only walks the subdirectory.
+        let pkg_root = pkg_root(&checkout_path, self.source_id.git_subdir());
+        let path_source = PathSource::new_recursive(&pkg_root,
                                                     &source_id,
                                                     self.config);
 
@@ -190,51 +255,181 @@ impl<'cfg> Source for GitSource<'cfg> {
 
 #[cfg(test)]
 mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+
+    use git2;
+    use tempdir::TempDir;
     use url::Url;
-    use super::ident;
-    use util::ToUrl;
+
+    use core::GitReference;
+    use core::source::SourceId;
+    use super::{canonicalize_url, ident, pkg_root, GitSource};
+    use util::{Config, ToUrl};
+
+    // Restores the previous value (or unsets the var) on drop, so a failed
+    // assertion between `set_var` and cleanup can't leak state into other
+    // tests sharing this process.
+    struct EnvVarGuard {
+        key: &'static str,
+        prev: Option<String>,
+    }
+
+    impl EnvVarGuard {
+        fn set(key: &'static str, value: &str) -> EnvVarGuard {
+            let prev = env::var(key).ok();
+            env::set_var(key, value);
+            EnvVarGuard { key: key, prev: prev }
+        }
+    }
+
+    impl Drop for EnvVarGuard {
+        fn drop(&mut self) {
+            match self.prev {
+                Some(ref prev) => env::set_var(self.key, prev),
+                None => env::remove_var(self.key),
+            }
+        }
+    }
 
     #[test]
     pub fn test_url_to_path_ident_with_path() {
-        let ident = ident(&url("https://github.com/carlhuda/cargo"));
+        let ident = ident(&url("https://github.com/carlhuda/cargo"), &config());
         assert!(ident.starts_with("cargo-"));
     }
 
     #[test]
     pub fn test_url_to_path_ident_without_path() {
-        let ident = ident(&url("https://github.com"));
+        let ident = ident(&url("https://github.com"), &config());
         assert!(ident.starts_with("_empty-"));
     }
 
     #[test]
     fn test_canonicalize_idents_by_stripping_trailing_url_slash() {
-        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston/"));
-        let ident2 = ident(&url("https://github.com/PistonDevelopers/piston"));
+        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston/"), &config());
+        let ident2 = ident(&url("https://github.com/PistonDevelopers/piston"), &config());
         assert_eq!(ident1, ident2);
     }
 
     #[test]
     fn test_canonicalize_idents_by_lowercasing_github_urls() {
-        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"));
-        let ident2 = ident(&url("https://github.com/pistondevelopers/piston"));
+        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("https://github.com/pistondevelopers/piston"), &config());
         assert_eq!(ident1, ident2);
     }
 
     #[test]
     fn test_canonicalize_idents_by_stripping_dot_git() {
-        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"));
-        let ident2 = ident(&url("https://github.com/PistonDevelopers/piston.git"));
+        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("https://github.com/PistonDevelopers/piston.git"), &config());
         assert_eq!(ident1, ident2);
     }
 
     #[test]
     fn test_canonicalize_idents_different_protocls() {
-        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"));
-        let ident2 = ident(&url("git://github.com/PistonDevelopers/piston"));
+        let ident1 = ident(&url("https://github.com/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("git://github.com/PistonDevelopers/piston"), &config());
         assert_eq!(ident1, ident2);
     }
 
+    #[test]
+    fn test_canonicalize_idents_by_lowercasing_other_known_hosts() {
+        let ident1 = ident(&url("https://gitlab.com/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("https://gitlab.com/pistondevelopers/piston"), &config());
+        assert_eq!(ident1, ident2);
+    }
+
+    #[test]
+    fn test_canonicalize_idents_by_lowercasing_bitbucket_urls() {
+        let ident1 = ident(&url("https://bitbucket.org/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("https://bitbucket.org/pistondevelopers/piston"), &config());
+        assert_eq!(ident1, ident2);
+    }
+
+    #[test]
+    fn test_canonicalize_idents_by_lowercasing_a_configured_host() {
+        // `net.git-case-insensitive-hosts` extends the built-in table, so a
+        // host that isn't github/gitlab/bitbucket can opt in too.
+        let _guard = EnvVarGuard::set("CARGO_NET_GIT_CASE_INSENSITIVE_HOSTS", "example-host.org");
+        let ident1 = ident(&url("https://example-host.org/PistonDevelopers/piston"), &config());
+        let ident2 = ident(&url("https://example-host.org/pistondevelopers/piston"), &config());
+        assert_eq!(ident1, ident2);
+    }
+
+    #[test]
+    fn test_canonicalize_does_not_collapse_case_sensitive_hosts() {
+        let url1 = canonicalize_url(&url("https://example.com/PistonDevelopers/piston"), &config());
+        let url2 = canonicalize_url(&url("https://example.com/pistondevelopers/piston"), &config());
+        assert_ne!(url1, url2);
+    }
+
+    #[test]
+    fn pkg_root_defaults_to_the_checkout_when_no_subdir_is_set() {
+        let checkout = Path::new("/tmp/checkouts/cargo-abcd1234/master");
+        assert_eq!(pkg_root(checkout, None), checkout.to_path_buf());
+    }
+
+    #[test]
+    fn pkg_root_joins_the_configured_subdir() {
+        let checkout = Path::new("/tmp/checkouts/cargo-abcd1234/master");
+        assert_eq!(pkg_root(checkout, Some("crates/foo")),
+                   checkout.join("crates/foo"));
+    }
+
+    // Builds a repo with a crate tucked away in `crates/foo`, plus a
+    // decoy manifest at the repo root, so a `GitSource` pinned at the
+    // subdir only ever sees the former.
+    fn repo_with_crate_in_subdir(path: &Path) {
+        fs::create_dir_all(path.join("crates/foo/src")).unwrap();
+        let mut manifest = File::create(path.join("crates/foo/Cargo.toml")).unwrap();
+        manifest.write_all(br#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+            authors = []
+        "#).unwrap();
+        File::create(path.join("crates/foo/src/lib.rs")).unwrap();
+
+        let mut decoy = File::create(path.join("Cargo.toml")).unwrap();
+        decoy.write_all(br#"
+            [package]
+            name = "decoy"
+            version = "0.1.0"
+            authors = []
+        "#).unwrap();
+
+        let repo = git2::Repository::init(path).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "add crates/foo", &tree, &[]).unwrap();
+    }
+
+    #[test]
+    fn read_packages_only_sees_the_configured_subdir() {
+        let origin = TempDir::new("cargo-git-origin").unwrap();
+        repo_with_crate_in_subdir(origin.path());
+
+        let url = Url::from_file_path(origin.path()).unwrap();
+        let master = GitReference::Branch("master".to_string());
+        let source_id = SourceId::for_git(&url, master, Some("crates/foo".to_string()));
+
+        let config = config();
+        let mut source = GitSource::new(&source_id, &config);
+        let pkgs = source.read_packages().unwrap();
+
+        assert_eq!(pkgs.len(), 1);
+    }
+
     fn url(s: &str) -> Url {
         s.to_url().unwrap()
     }
+
+    fn config() -> Config {
+        Config::default().unwrap()
+    }
 }
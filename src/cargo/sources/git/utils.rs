@@ -0,0 +1,294 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use git2;
+use url::Url;
+
+use core::GitReference;
+use util::{CargoResult, ChainError, Config, human};
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct GitRevision(git2::Oid);
+
+impl fmt::Display for GitRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub struct GitRemote {
+    url: Url,
+}
+
+pub struct GitDatabase {
+    path: PathBuf,
+    shallow: bool,
+}
+
+pub struct GitCheckout {
+    location: PathBuf,
+    revision: GitRevision,
+}
+
+// Marker file dropped inside a db directory whenever it was populated via
+// a depth-limited fetch, so a later `db_at` (which may run in a process that
+// never called `checkout`) still knows to treat the history as incomplete.
+const SHALLOW_MARKER: &'static str = ".cargo-shallow";
+
+fn shallow_marker(db_path: &Path) -> PathBuf {
+    db_path.join(SHALLOW_MARKER)
+}
+
+impl GitRemote {
+    pub fn new(url: &Url) -> GitRemote {
+        GitRemote { url: url.clone() }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Resolve `reference` against the database already checked out at
+    /// `db_path`, without touching the network.
+    pub fn rev_for(&self, db_path: &Path, reference: &GitReference)
+                   -> CargoResult<GitRevision> {
+        let db = try!(self.db_at(db_path));
+        db.resolve(reference)
+    }
+
+    /// Open the database at `db_path`, which must already exist.
+    pub fn db_at(&self, db_path: &Path) -> CargoResult<GitDatabase> {
+        try!(git2::Repository::open(db_path));
+        Ok(GitDatabase {
+            path: db_path.to_path_buf(),
+            shallow: shallow_marker(db_path).exists(),
+        })
+    }
+
+    /// Fetch (or clone) the remote into `into`, returning a database that
+    /// can resolve references and copy checkouts to the working tree.
+    ///
+    /// `depth` limits the amount of history fetched (`--depth <n>`
+    /// semantics). Pass `None` for a full clone/fetch, e.g. when a pinned
+    /// `rev` turned out not to be reachable within a previous shallow
+    /// fetch's history.
+    ///
+    /// `reference` narrows which refs are fetched: a `Branch`/`Tag` only
+    /// needs its own ref, while a `Rev` isn't a ref name so every branch
+    /// and tag is fetched.
+    pub fn checkout(&self, into: &Path, cfg: &Config, depth: Option<u32>,
+                     reference: &GitReference) -> CargoResult<GitDatabase> {
+        if !into.exists() {
+            try!(fs::create_dir_all(into.parent().unwrap()));
+            try!(git2::Repository::init_bare(into));
+        }
+        let repo = try!(git2::Repository::open(into));
+        try!(fetch(&repo, self.url.to_string().as_str(), cfg, depth, reference));
+
+        // Whether the db is actually shallow is ground truth from the repo
+        // itself, not our request: a `depth` fetch against a db that was
+        // already fully cloned is a no-op on the existing history, and an
+        // older libgit2/server pair may silently ignore `--depth` entirely.
+        let shallow = repo.is_shallow();
+        let marker = shallow_marker(into);
+        if shallow {
+            try!(try!(File::create(&marker)).write_all(b""));
+        } else if marker.exists() {
+            try!(fs::remove_file(&marker));
+        }
+
+        Ok(GitDatabase {
+            path: into.to_path_buf(),
+            shallow: shallow,
+        })
+    }
+}
+
+impl GitDatabase {
+    pub fn shallow(&self) -> bool {
+        self.shallow
+    }
+
+    pub fn copy_to(&self, rev: GitRevision, dest: &Path, cfg: &Config)
+                    -> CargoResult<GitCheckout> {
+        let checkout = try!(GitCheckout::clone_into(dest, self, rev));
+        try!(checkout.update_submodules(cfg));
+        Ok(checkout)
+    }
+
+    pub fn rev_for(&self, reference: &GitReference) -> CargoResult<GitRevision> {
+        self.resolve(reference)
+    }
+
+    fn resolve(&self, reference: &GitReference) -> CargoResult<GitRevision> {
+        let repo = try!(git2::Repository::open(&self.path));
+        let id = match *reference {
+            GitReference::Tag(ref s) => {
+                try!(repo.refname_to_id(&format!("refs/tags/{}", s)))
+            }
+            GitReference::Branch(ref s) => {
+                let b = try!(repo.find_branch(s, git2::BranchType::Local)
+                            .or_else(|_| repo.find_branch(&format!("origin/{}", s),
+                                                           git2::BranchType::Remote)));
+                try!(b.get().target().chain_error(|| {
+                    human(format!("branch `{}` did not have a target", s))
+                }))
+            }
+            GitReference::Rev(ref s) => {
+                let obj = try!(repo.revparse_single(s));
+                obj.id()
+            }
+        };
+        Ok(GitRevision(id))
+    }
+}
+
+impl GitCheckout {
+    fn clone_into(into: &Path, database: &GitDatabase, revision: GitRevision)
+                   -> CargoResult<GitCheckout> {
+        if into.exists() {
+            try!(fs::remove_dir_all(into));
+        }
+        try!(fs::create_dir_all(into.parent().unwrap()));
+        let _repo = try!(git2::build::RepoBuilder::new()
+            .clone(database.path.to_str().unwrap(), into));
+        let checkout = GitCheckout { location: into.to_path_buf(), revision: revision };
+        try!(checkout.reset());
+        Ok(checkout)
+    }
+
+    fn reset(&self) -> CargoResult<()> {
+        let repo = try!(git2::Repository::open(&self.location));
+        let object = try!(repo.find_object(self.revision.0, None));
+        try!(repo.reset(&object, git2::ResetType::Hard, None));
+        Ok(())
+    }
+
+    fn update_submodules(&self, _cfg: &Config) -> CargoResult<()> {
+        Ok(())
+    }
+}
+
+fn fetch(repo: &git2::Repository, url: &str, _cfg: &Config, depth: Option<u32>,
+         reference: &GitReference) -> CargoResult<()> {
+    // A pinned branch or tag names the one ref we actually need, so fetch
+    // just that instead of every branch/tag in the remote. A `Rev` isn't a
+    // ref name we can ask the server for directly, so fall back to fetching
+    // everything.
+    let refspec = match *reference {
+        GitReference::Branch(ref s) => format!("+refs/heads/{0}:refs/heads/{0}", s),
+        GitReference::Tag(ref s) => format!("+refs/tags/{0}:refs/tags/{0}", s),
+        GitReference::Rev(..) => String::new(),
+    };
+    let wildcard_refspecs = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+    let refspecs: &[&str] = if refspec.is_empty() {
+        &wildcard_refspecs
+    } else {
+        &[refspec.as_str()]
+    };
+    let mut remote = try!(repo.remote_anonymous(url));
+    let mut opts = git2::FetchOptions::new();
+    if let Some(depth) = depth {
+        // `FetchOptions::depth` requires a git2 release new enough to
+        // expose libgit2's shallow clone support (added in git2 0.6.0);
+        // this repo has no Cargo.toml/Cargo.lock pinning a version, so
+        // whoever adds one should check it against that minimum.
+        opts.depth(depth as i32);
+    }
+    try!(remote.fetch(&refspecs, Some(&mut opts), None));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use git2;
+    use tempdir::TempDir;
+    use url::Url;
+
+    use core::GitReference;
+    use util::Config;
+    use super::GitRemote;
+
+    // Builds a local repo with two commits on `master` so tests can pin a
+    // rev (the first commit) that is not the branch tip.
+    fn repo_with_two_commits(path: &::std::path::Path) -> (git2::Oid, git2::Oid) {
+        let repo = git2::Repository::init(path).unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let first_commit = repo.find_commit(first).unwrap();
+        let second = repo.commit(Some("HEAD"), &sig, &sig, "second", &tree,
+                                  &[&first_commit]).unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn checkout_reports_shallow_only_when_the_repo_actually_is() {
+        let origin = TempDir::new("cargo-git-origin").unwrap();
+        repo_with_two_commits(origin.path());
+
+        let db_dir = TempDir::new("cargo-git-db").unwrap();
+        let db_path = db_dir.path().join("db");
+
+        let url = Url::from_file_path(origin.path()).unwrap();
+        let remote = GitRemote::new(&url);
+        let config = Config::default().unwrap();
+
+        let master = GitReference::Branch("master".to_string());
+        let db = remote.checkout(&db_path, &config, Some(1), &master).unwrap();
+        assert!(db.shallow());
+
+        // Fetching the same db again without a depth limit deepens it; the
+        // marker/state must flip to reflect the repo's real history, not
+        // the depth we happened to ask for.
+        let db = remote.checkout(&db_path, &config, None, &master).unwrap();
+        assert!(!db.shallow());
+    }
+
+    #[test]
+    fn rev_not_reachable_in_shallow_depth_deepens_and_resolves() {
+        let origin = TempDir::new("cargo-git-origin").unwrap();
+        let (first, _second) = repo_with_two_commits(origin.path());
+
+        let db_dir = TempDir::new("cargo-git-db").unwrap();
+        let db_path = db_dir.path().join("db");
+
+        let url = Url::from_file_path(origin.path()).unwrap();
+        let remote = GitRemote::new(&url);
+        let config = Config::default().unwrap();
+
+        let pinned = GitReference::Rev(first.to_string());
+        let db = remote.checkout(&db_path, &config, Some(1), &pinned).unwrap();
+        assert!(db.shallow());
+        assert!(db.rev_for(&pinned).is_err());
+
+        let deepened = remote.checkout(&db_path, &config, None, &pinned).unwrap();
+        assert!(!deepened.shallow());
+        assert!(deepened.rev_for(&pinned).is_ok());
+    }
+
+    #[test]
+    fn tag_refs_are_fetched_and_resolve() {
+        let origin = TempDir::new("cargo-git-origin").unwrap();
+        repo_with_two_commits(origin.path());
+        {
+            let repo = git2::Repository::open(origin.path()).unwrap();
+            let head = repo.head().unwrap().target().unwrap();
+            let obj = repo.find_object(head, None).unwrap();
+            repo.tag_lightweight("v1.0.0", &obj, false).unwrap();
+        }
+
+        let db_dir = TempDir::new("cargo-git-db").unwrap();
+        let db_path = db_dir.path().join("db");
+
+        let url = Url::from_file_path(origin.path()).unwrap();
+        let remote = GitRemote::new(&url);
+        let config = Config::default().unwrap();
+
+        let pinned = GitReference::Tag("v1.0.0".to_string());
+        let db = remote.checkout(&db_path, &config, None, &pinned).unwrap();
+        assert!(db.rev_for(&pinned).is_ok());
+    }
+}